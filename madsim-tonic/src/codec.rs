@@ -1,18 +1,31 @@
 use crate::Status;
 use async_stream::try_stream;
 use futures::{Stream, StreamExt};
-use madsim::task::JoinHandle;
+use madsim::{net::Priority, task::JoinHandle};
 use std::{
     fmt,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
 };
+use tokio::sync::Semaphore;
 use tonic::codegen::BoxStream;
 
+/// Default number of messages the sender may have in flight before it must
+/// wait for the receiver to drain some of them.
+const DEFAULT_WINDOW: usize = 16;
+
 /// Streaming requests and responses.
 pub struct Streaming<T> {
     stream: BoxStream<T>,
+    /// The priority recorded for this stream, surfaced through
+    /// [`priority`](Self::priority). `Streaming` sends nothing itself.
+    priority: Priority,
+    /// HTTP/2-style send credit: a permit is returned here each time
+    /// [`message`](Self::message) hands out a message, so a sender that
+    /// acquires a permit before emitting each message can't outrun a slow
+    /// consumer by more than `window` messages.
+    credit: Arc<Semaphore>,
 }
 
 impl<T: Send + 'static> Streaming<T> {
@@ -25,6 +38,88 @@ impl<T: Send + 'static> Streaming<T> {
         tag: u64,
         request_sending_task: Option<JoinHandle<()>>,
     ) -> Self {
+        Self::with_options(
+            ep,
+            tag,
+            request_sending_task,
+            Priority::NORMAL,
+            Arc::new(Semaphore::new(DEFAULT_WINDOW)),
+        )
+    }
+
+    /// Like [`new`](Self::new), but records `priority` instead of
+    /// [`Priority::NORMAL`].
+    ///
+    /// The priority is only stored and returned by
+    /// [`priority`](Self::priority); it affects scheduling only for sends
+    /// that pass it to `Endpoint::send_to_raw_with_priority`.
+    ///
+    /// Not a public API.
+    #[doc(hidden)]
+    pub fn with_priority(
+        ep: Arc<madsim::net::Endpoint>,
+        tag: u64,
+        request_sending_task: Option<JoinHandle<()>>,
+        priority: Priority,
+    ) -> Self {
+        Self::with_options(
+            ep,
+            tag,
+            request_sending_task,
+            priority,
+            Arc::new(Semaphore::new(DEFAULT_WINDOW)),
+        )
+    }
+
+    /// Like [`new`](Self::new), but the credit pool starts with `window`
+    /// permits instead of [`DEFAULT_WINDOW`].
+    ///
+    /// The pool bounds only a sender that draws permits from it, reached
+    /// through [`credit`](Self::credit); `request_sending_task` does not.
+    ///
+    /// Not a public API.
+    #[doc(hidden)]
+    pub fn with_window(
+        ep: Arc<madsim::net::Endpoint>,
+        tag: u64,
+        request_sending_task: Option<JoinHandle<()>>,
+        window: usize,
+    ) -> Self {
+        Self::with_options(
+            ep,
+            tag,
+            request_sending_task,
+            Priority::NORMAL,
+            Arc::new(Semaphore::new(window)),
+        )
+    }
+
+    /// Creates a new streaming with both the priority and the flow-control
+    /// credit pool configured explicitly.
+    ///
+    /// `credit` is taken (rather than built internally from a window size) so
+    /// that a bi-directional caller can clone it into `request_sending_task`
+    /// *before* spawning that task, and have the sender await a permit here
+    /// before emitting each message -- the same pool this stream returns a
+    /// permit to every time [`message`](Self::message) is polled.
+    ///
+    /// Not a public API.
+    ///
+    /// This is the flow-control *primitive*: nothing in this crate connects
+    /// a `request_sending_task` or a server response emitter to `credit`, so
+    /// the pool bounds only a sender that acquires a permit from it before
+    /// each send, as the hand-rolled sender task in
+    /// [`sender_blocks_until_consumer_drains_credit`](tests::sender_blocks_until_consumer_drains_credit)
+    /// does.
+    #[doc(hidden)]
+    pub fn with_options(
+        ep: Arc<madsim::net::Endpoint>,
+        tag: u64,
+        request_sending_task: Option<JoinHandle<()>>,
+        priority: Priority,
+        credit: Arc<Semaphore>,
+    ) -> Self {
+        let credit_ = credit.clone();
         Streaming {
             stream: try_stream! {
                 // For bi-directional streaming, we spawn a task to send requests.
@@ -36,10 +131,16 @@ impl<T: Send + 'static> Streaming<T> {
                     if msg.downcast_ref::<StreamEnd>().is_some() {
                         return;
                     }
+                    // The item is handed to the consumer by this very poll
+                    // of `message()`; return the credit it frees up to the
+                    // sender now, not on the consumer's next poll.
+                    credit_.add_permits(1);
                     yield *msg.downcast::<T>().unwrap();
                 }
             }
             .boxed(),
+            priority,
+            credit,
         }
     }
 
@@ -48,7 +149,33 @@ impl<T: Send + 'static> Streaming<T> {
     /// This method is used by macros only. Not a public API.
     #[doc(hidden)]
     pub fn from_stream(stream: BoxStream<T>) -> Self {
-        Streaming { stream }
+        Streaming {
+            stream,
+            priority: Priority::NORMAL,
+            credit: Arc::new(Semaphore::new(DEFAULT_WINDOW)),
+        }
+    }
+
+    /// Returns the priority recorded for this stream.
+    ///
+    /// Not a public API: lets the code sending requests/responses alongside
+    /// this stream tag them with the same priority.
+    #[doc(hidden)]
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Returns the flow-control credit pool the sending side must draw a
+    /// permit from before emitting each message.
+    ///
+    /// Not a public API. Prefer passing your own pool to
+    /// [`with_options`](Self::with_options) and cloning it into the sender
+    /// directly; this exists for callers that received a `Streaming` built
+    /// by [`new`](Self::new)/[`with_priority`](Self::with_priority) and need
+    /// to recover the pool it ended up with.
+    #[doc(hidden)]
+    pub fn credit(&self) -> Arc<Semaphore> {
+        self.credit.clone()
     }
 }
 
@@ -75,3 +202,72 @@ impl<T> Stream for Streaming<T> {
         Pin::new(&mut self.stream).poll_next(cx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use madsim::{net::Endpoint, runtime::Runtime};
+
+    /// Stands in for a real `request_sending_task`/response emitter, which
+    /// don't exist in this crate yet (they're generated code): a plain task
+    /// that acquires a permit from the same `credit` pool before each send,
+    /// same as a real one would.
+    #[test]
+    fn sender_blocks_until_consumer_drains_credit() {
+        let runtime = Runtime::new();
+        let addr = "10.0.0.1:1".parse().unwrap();
+        let node = runtime.create_node().ip(addr.ip()).build();
+
+        let f = node.spawn(async move {
+            let ep = Arc::new(Endpoint::bind(addr).await.unwrap());
+            let credit = Arc::new(Semaphore::new(1));
+            let sender_credit = credit.clone();
+            let ep_send = ep.clone();
+            let second_send_done = Arc::new(tokio::sync::Notify::new());
+            let notify = second_send_done.clone();
+
+            let sender = madsim::task::spawn(async move {
+                // The window is 1, so this first send consumes the only
+                // initially-available permit without blocking.
+                let permit = sender_credit.clone().acquire_owned().await.unwrap();
+                ep_send
+                    .send_to_raw(addr, 0, Box::new(1u32) as Box<dyn std::any::Any + Send + Sync>)
+                    .await
+                    .unwrap();
+                permit.forget();
+                // The second send must block until the consumer drains the
+                // first message and a permit is returned.
+                let permit = sender_credit.clone().acquire_owned().await.unwrap();
+                ep_send
+                    .send_to_raw(addr, 1, Box::new(2u32) as Box<dyn std::any::Any + Send + Sync>)
+                    .await
+                    .unwrap();
+                permit.forget();
+                notify.notify_one();
+            });
+
+            let mut stream = Streaming::<u32>::with_options(
+                ep,
+                0,
+                None,
+                Priority::NORMAL,
+                credit.clone(),
+            );
+
+            // Let the sender run up to its second `acquire_owned()`.
+            madsim::time::sleep(std::time::Duration::from_millis(10)).await;
+            // No permit has been returned yet: the sender's second send
+            // should still be blocked on `sender_credit.acquire_owned()`.
+            assert!(credit.try_acquire().is_err());
+            assert_eq!(stream.message().await.unwrap(), Some(1));
+
+            // Consuming the first message returns a permit, which should
+            // unblock the sender's second send.
+            second_send_done.notified().await;
+            assert_eq!(stream.message().await.unwrap(), Some(2));
+            sender.await.unwrap();
+        });
+
+        runtime.block_on(f).unwrap();
+    }
+}
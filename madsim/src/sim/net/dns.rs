@@ -0,0 +1,116 @@
+//! Simulated DNS resolution.
+//!
+//! Hostnames are resolved through [`NetSim`]'s host table rather than the OS
+//! resolver, so reconnection/failover logic that depends on DNS behavior
+//! (stale records, slow resolvers, partial resolution) can be driven
+//! deterministically from a test.
+
+use super::NetSim;
+use crate::plugin;
+use std::{future::Future, io, net::SocketAddr, pin::Pin};
+
+/// A madsim equivalent of `tokio::net::ToSocketAddrs`.
+///
+/// Implemented for [`SocketAddr`] (a no-op) and for `&str`/[`String`] (resolved
+/// through [`NetSim`]'s simulated DNS table).
+pub trait ToSocketAddrs: Send + Sync {
+    /// Resolves `self` to a single socket address.
+    fn to_socket_addr(&self) -> Pin<Box<dyn Future<Output = io::Result<SocketAddr>> + Send + '_>>;
+}
+
+impl ToSocketAddrs for SocketAddr {
+    fn to_socket_addr(&self) -> Pin<Box<dyn Future<Output = io::Result<SocketAddr>> + Send + '_>> {
+        let addr = *self;
+        Box::pin(async move { Ok(addr) })
+    }
+}
+
+impl ToSocketAddrs for str {
+    fn to_socket_addr(&self) -> Pin<Box<dyn Future<Output = io::Result<SocketAddr>> + Send + '_>> {
+        if let Ok(addr) = self.parse::<SocketAddr>() {
+            return Box::pin(async move { Ok(addr) });
+        }
+        let name = self.to_owned();
+        Box::pin(async move { plugin::simulator::<NetSim>().resolve(&name).await })
+    }
+}
+
+impl ToSocketAddrs for String {
+    fn to_socket_addr(&self) -> Pin<Box<dyn Future<Output = io::Result<SocketAddr>> + Send + '_>> {
+        self.as_str().to_socket_addr()
+    }
+}
+
+impl ToSocketAddrs for &str {
+    fn to_socket_addr(&self) -> Pin<Box<dyn Future<Output = io::Result<SocketAddr>> + Send + '_>> {
+        (*self).to_socket_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{runtime::Runtime, time::Instant};
+    use std::time::Duration;
+
+    #[test]
+    fn resolve_respects_configured_latency() {
+        let runtime = Runtime::new();
+        let addr = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let node = runtime.create_node().ip(addr.ip()).build();
+
+        let f = node.spawn(async move {
+            let net = plugin::simulator::<NetSim>();
+            net.register_host("example.test", addr);
+            net.set_resolve_latency("example.test", Duration::from_millis(300));
+
+            let start = Instant::now();
+            let resolved = net.resolve("example.test").await.unwrap();
+            assert_eq!(resolved, addr);
+            assert!(Instant::now().saturating_duration_since(start) >= Duration::from_millis(300));
+        });
+
+        runtime.block_on(f).unwrap();
+    }
+
+    #[test]
+    fn resolve_respects_failure_rate() {
+        let runtime = Runtime::new();
+        let addr = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let node = runtime.create_node().ip(addr.ip()).build();
+
+        let f = node.spawn(async move {
+            let net = plugin::simulator::<NetSim>();
+            net.register_host("example.test", addr);
+            net.set_resolve_failure_rate("example.test", 1.0);
+
+            net.resolve("example.test")
+                .await
+                .expect_err("a 100% configured failure rate must fail every lookup");
+        });
+
+        runtime.block_on(f).unwrap();
+    }
+
+    #[test]
+    fn resolve_round_robins_multi_record_hosts() {
+        let runtime = Runtime::new();
+        let addr = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr_a = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let addr_b = "10.0.0.3:1".parse::<SocketAddr>().unwrap();
+        let node = runtime.create_node().ip(addr.ip()).build();
+
+        let f = node.spawn(async move {
+            let net = plugin::simulator::<NetSim>();
+            net.register_host_multi("example.test", vec![addr_a, addr_b]);
+
+            let mut resolved = Vec::new();
+            for _ in 0..4 {
+                resolved.push(net.resolve("example.test").await.unwrap());
+            }
+            assert_eq!(resolved, vec![addr_a, addr_b, addr_a, addr_b]);
+        });
+
+        runtime.block_on(f).unwrap();
+    }
+}
@@ -0,0 +1,353 @@
+//! A simulated UDP-style endpoint, multiplexing tagged messages over a
+//! priority-ordered send scheduler.
+
+use crate::{net::NetSim, plugin};
+use once_cell::sync::Lazy;
+use std::{
+    any::Any,
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, VecDeque},
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::sync::oneshot;
+
+/// An endpoint message payload.
+pub type Payload = Box<dyn Any + Send + Sync>;
+
+/// The number of bytes a single scheduler "slot" carries.
+///
+/// Payloads larger than this are not physically split (they remain a single
+/// Rust value end to end), but occupy the link for `ceil(len / CHUNK_SIZE)`
+/// scheduling slots, so a high-priority message queued behind a large one
+/// can be interleaved between slots instead of waiting for the whole body.
+pub(crate) const CHUNK_SIZE: usize = 4096;
+
+/// Send priority for an [`Endpoint`] message.
+///
+/// Higher values are serviced first. Frames of equal priority are serviced
+/// in the order they were enqueued (FIFO).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Priority(pub u8);
+
+impl Priority {
+    /// The default priority used by [`Endpoint::send_to_raw`].
+    pub const NORMAL: Priority = Priority(0);
+    /// A priority high enough to preempt normal-priority bulk transfers.
+    pub const HIGH: Priority = Priority(255);
+}
+
+enum Slot {
+    /// The message has already arrived and is waiting to be picked up.
+    Buffered(VecDeque<(Payload, SocketAddr)>),
+    /// A receiver is waiting for the next message with this tag.
+    Waiting(oneshot::Sender<(Payload, SocketAddr)>),
+}
+
+struct EndpointInner {
+    addr: SocketAddr,
+    tags: Mutex<HashMap<u64, Slot>>,
+}
+
+impl EndpointInner {
+    fn deliver(&self, tag: u64, msg: Payload, from: SocketAddr) {
+        let mut tags = self.tags.lock().unwrap();
+        match tags.remove(&tag) {
+            Some(Slot::Waiting(tx)) => {
+                let _ = tx.send((msg, from));
+            }
+            Some(Slot::Buffered(mut q)) => {
+                q.push_back((msg, from));
+                tags.insert(tag, Slot::Buffered(q));
+            }
+            None => {
+                let mut q = VecDeque::new();
+                q.push_back((msg, from));
+                tags.insert(tag, Slot::Buffered(q));
+            }
+        }
+    }
+}
+
+static ENDPOINTS: Lazy<Mutex<HashMap<SocketAddr, Arc<EndpointInner>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A simulated endpoint for sending/receiving tagged, unordered messages.
+///
+/// This mirrors the raw send/recv primitives that higher-level RPC layers
+/// (e.g. madsim-tonic) are built on.
+pub struct Endpoint {
+    inner: Arc<EndpointInner>,
+}
+
+impl Endpoint {
+    /// Binds an endpoint to the given address, which may be a [`SocketAddr`]
+    /// or a hostname registered via [`NetSim::register_host`](crate::net::NetSim::register_host).
+    pub async fn bind(addr: impl crate::net::ToSocketAddrs) -> io::Result<Self> {
+        let addr = addr.to_socket_addr().await?;
+        let inner = Arc::new(EndpointInner {
+            addr,
+            tags: Mutex::new(HashMap::new()),
+        });
+        ENDPOINTS.lock().unwrap().insert(addr, inner.clone());
+        Ok(Endpoint { inner })
+    }
+
+    /// Returns the local address this endpoint is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.inner.addr)
+    }
+
+    /// Sends a message to `dst` tagged with `tag`, at normal priority.
+    pub async fn send_to_raw(&self, dst: SocketAddr, tag: u64, msg: Payload) -> io::Result<()> {
+        self.send_to_raw_with_priority(dst, tag, msg, Priority::NORMAL, 0)
+            .await
+    }
+
+    /// Sends a message to `dst` tagged with `tag`, at the given `priority`.
+    ///
+    /// `len_hint` is the approximate encoded size of `msg` in bytes; it only
+    /// affects how many scheduling slots the message occupies (and thus how
+    /// long it can hold up lower-priority traffic behind it), not the actual
+    /// transfer of `msg` itself.
+    pub async fn send_to_raw_with_priority(
+        &self,
+        dst: SocketAddr,
+        tag: u64,
+        msg: Payload,
+        priority: Priority,
+        len_hint: usize,
+    ) -> io::Result<()> {
+        // Roll the link's drop probability once for the whole frame here,
+        // rather than once per chunk in the scheduler loop below: a frame
+        // split into N chunks must not compound into an effective drop rate
+        // of `1 - (1 - loss)^N`.
+        let dropped = plugin::simulator::<NetSim>().should_drop(self.inner.addr, dst);
+        let scheduler = Scheduler::get_or_create(self.inner.addr, dst);
+        scheduler.enqueue(Frame {
+            tag,
+            msg: Some(msg),
+            dropped,
+            priority,
+            seq: NEXT_SEQ.fetch_add(1, Ordering::Relaxed),
+            chunks_remaining: len_hint.div_ceil(CHUNK_SIZE).max(1),
+        });
+        Ok(())
+    }
+
+    /// Receives the next message tagged with `tag`.
+    pub async fn recv_from_raw(&self, tag: u64) -> io::Result<(Payload, SocketAddr)> {
+        let rx = {
+            let mut tags = self.inner.tags.lock().unwrap();
+            match tags.remove(&tag) {
+                Some(Slot::Buffered(mut q)) => {
+                    if let Some(item) = q.pop_front() {
+                        if !q.is_empty() {
+                            tags.insert(tag, Slot::Buffered(q));
+                        }
+                        return Ok(item);
+                    }
+                    None
+                }
+                Some(Slot::Waiting(_)) | None => {
+                    let (tx, rx) = oneshot::channel();
+                    tags.insert(tag, Slot::Waiting(tx));
+                    Some(rx)
+                }
+            }
+        };
+        match rx {
+            Some(rx) => rx
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, "endpoint closed")),
+            None => unreachable!(),
+        }
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        ENDPOINTS.lock().unwrap().remove(&self.inner.addr);
+    }
+}
+
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+struct Frame {
+    tag: u64,
+    msg: Option<Payload>,
+    /// Whether the link's loss model already decided (once, for the whole
+    /// frame) that this message should be dropped rather than delivered.
+    dropped: bool,
+    priority: Priority,
+    seq: u64,
+    chunks_remaining: usize,
+}
+
+/// Order frames by priority (descending), then FIFO by insertion sequence.
+impl PartialEq for Frame {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority && self.seq == other.seq
+    }
+}
+impl Eq for Frame {}
+impl PartialOrd for Frame {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Frame {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.priority, Reverse(self.seq)).cmp(&(other.priority, Reverse(other.seq)))
+    }
+}
+
+/// A per-(src, dst) send scheduler: holds queued frames ordered by priority
+/// and dispatches them one chunk-slot at a time, so a newly-enqueued
+/// high-priority frame can preempt an in-flight bulk transfer at the next
+/// chunk boundary.
+struct Scheduler {
+    dst: SocketAddr,
+    queue: Mutex<BinaryHeap<Frame>>,
+    notify: tokio::sync::Notify,
+}
+
+static SCHEDULERS: Lazy<Mutex<HashMap<(SocketAddr, SocketAddr), Arc<Scheduler>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+impl Scheduler {
+    fn get_or_create(src: SocketAddr, dst: SocketAddr) -> Arc<Scheduler> {
+        let mut schedulers = SCHEDULERS.lock().unwrap();
+        schedulers
+            .entry((src, dst))
+            .or_insert_with(|| {
+                let scheduler = Arc::new(Scheduler {
+                    dst,
+                    queue: Mutex::new(BinaryHeap::new()),
+                    notify: tokio::sync::Notify::new(),
+                });
+                crate::task::spawn(Self::run(scheduler.clone(), src)).detach();
+                scheduler
+            })
+            .clone()
+    }
+
+    fn enqueue(&self, frame: Frame) {
+        self.queue.lock().unwrap().push(frame);
+        self.notify.notify_one();
+    }
+
+    async fn run(self: Arc<Self>, src: SocketAddr) {
+        loop {
+            let popped = self.queue.lock().unwrap().pop();
+            let mut frame = match popped {
+                Some(frame) => frame,
+                // `Notify::notify_one` stores a wakeup permit even when called
+                // before we start waiting, so an `enqueue` racing with the
+                // empty check above can't be missed here.
+                None => {
+                    self.notify.notified().await;
+                    continue;
+                }
+            };
+            // Per-chunk delay only (latency/bandwidth serialization); the
+            // drop decision for this frame was already made once, in
+            // `send_to_raw_with_priority`.
+            let net = plugin::simulator::<NetSim>();
+            let delay = net.delay_for_reliable(src, self.dst, CHUNK_SIZE);
+            if !delay.is_zero() {
+                crate::time::sleep(delay).await;
+            }
+            frame.chunks_remaining = frame.chunks_remaining.saturating_sub(1);
+            if frame.chunks_remaining == 0 {
+                if let (false, Some(msg)) = (frame.dropped, frame.msg.take()) {
+                    if let Some(endpoint) = ENDPOINTS.lock().unwrap().get(&self.dst).cloned() {
+                        endpoint.deliver(frame.tag, msg, src);
+                    }
+                }
+            } else {
+                self.queue.lock().unwrap().push(frame);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{net::LinkConfig, runtime::Runtime, time::Instant};
+    use std::time::Duration;
+    use tokio::sync::Barrier;
+
+    #[test]
+    fn high_priority_preempts_bulk_transfer() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let id1 = node1.id();
+        let id2 = node2.id();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f1 = node1.spawn(async move {
+            // A non-zero per-chunk delay is needed for the scheduler to
+            // actually yield between chunks and notice a newly-enqueued
+            // higher-priority frame, rather than draining the whole queue
+            // synchronously.
+            plugin::simulator::<NetSim>().set_link(
+                id1,
+                id2,
+                LinkConfig {
+                    latency: Duration::from_millis(1),
+                    ..Default::default()
+                },
+            );
+            let ep = Endpoint::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            // Queue a bulk transfer occupying many chunk slots...
+            ep.send_to_raw_with_priority(
+                addr2,
+                100,
+                Box::new(()) as Payload,
+                Priority::NORMAL,
+                CHUNK_SIZE * 50,
+            )
+            .await
+            .unwrap();
+            // ...then a small high-priority message behind it.
+            ep.send_to_raw_with_priority(addr2, 200, Box::new(()) as Payload, Priority::HIGH, 0)
+                .await
+                .unwrap();
+        });
+
+        let f2 = node2.spawn(async move {
+            let ep = Arc::new(Endpoint::bind(addr2).await.unwrap());
+            barrier.wait().await;
+            let ep_bulk = ep.clone();
+            let bulk = crate::task::spawn(async move {
+                ep_bulk.recv_from_raw(100).await.unwrap();
+                Instant::now()
+            });
+            let high = crate::task::spawn(async move {
+                ep.recv_from_raw(200).await.unwrap();
+                Instant::now()
+            });
+            let high_done = high.await.unwrap();
+            let bulk_done = bulk.await.unwrap();
+            assert!(
+                high_done < bulk_done,
+                "a high-priority message queued behind a bulk transfer should \
+                 preempt it instead of waiting for the whole transfer to finish"
+            );
+        });
+
+        runtime.block_on(f1).unwrap();
+        runtime.block_on(f2).unwrap();
+    }
+}
@@ -0,0 +1,596 @@
+//! Simulated network.
+
+use crate::{
+    rand::thread_rng,
+    task::NodeId,
+    time::Instant,
+};
+use rand::Rng;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Mutex, Weak},
+    time::Duration,
+};
+
+/// A live connection that can be torn down by the network simulator, e.g.
+/// when the node hosting one of its endpoints is reset.
+///
+/// Implemented by `TcpStream`'s shared connection state; registered with
+/// [`NetSim::register_connection`] when the connection is established.
+pub(crate) trait ResetHandle: Send + Sync {
+    /// Tears down the connection: both directions should observe EOF, and
+    /// any in-flight or future `flush` should report the connection as reset.
+    fn reset(&self);
+}
+
+pub mod dns;
+pub mod endpoint;
+pub mod tcp;
+
+pub use self::dns::ToSocketAddrs;
+pub use self::endpoint::{Endpoint, Priority};
+pub use self::tcp::{TcpListener, TcpStream};
+
+/// Configuration of a simulated network link between two nodes.
+///
+/// All fields are optional; a field left at its default does not affect
+/// scheduling (e.g. `loss: 0.0` never drops a frame).
+#[derive(Debug, Clone, Default)]
+pub struct LinkConfig {
+    /// Fixed one-way latency added to every frame sent over this link.
+    pub latency: Duration,
+    /// Extra latency drawn uniformly from `[0, jitter]` and added on top of `latency`.
+    pub jitter: Duration,
+    /// Bandwidth cap in bytes/sec. When set, a frame's transmission time is
+    /// `len / bandwidth`, and frames on the same link queue behind each other
+    /// (the link's next-free time), modeling serialization / head-of-line delay.
+    pub bandwidth: Option<f64>,
+    /// Probability in `[0, 1]` that a frame sent over this link is dropped.
+    pub loss: f64,
+    /// Number of in-flight frames that may be buffered and delivered out of
+    /// their send order. `0` disables reordering.
+    pub reorder_window: usize,
+}
+
+impl LinkConfig {
+    /// Rolls the link's independent per-frame drop probability once. Call
+    /// this (at most) once per frame, not once per chunk of a frame split
+    /// across multiple scheduling slots, or the effective drop rate compounds
+    /// far above the configured `loss`.
+    pub(crate) fn should_drop(&self) -> bool {
+        self.loss > 0.0 && thread_rng().gen_bool(self.loss)
+    }
+
+    /// Draws the delay to apply to a frame of `len` bytes sent over this
+    /// link, ignoring `loss` (the caller is assumed to have already decided
+    /// whether to drop the frame via [`should_drop`](Self::should_drop), or
+    /// to be a path like `TcpStream` where bytes must never be dropped).
+    ///
+    /// `next_free` is the link's current next-free virtual time (for
+    /// bandwidth serialization); it is updated in place.
+    pub(crate) fn delay(&self, len: usize, now: Instant, next_free: &mut Instant) -> Duration {
+        let mut rng = thread_rng();
+        let jitter = if self.jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            rng.gen_range(Duration::ZERO..=self.jitter)
+        };
+        let mut arrival = now + self.latency + jitter;
+        if let Some(bandwidth) = self.bandwidth {
+            let start = (*next_free).max(now);
+            let xmit = Duration::from_secs_f64(len as f64 / bandwidth);
+            *next_free = start + xmit;
+            arrival = arrival.max(start + xmit);
+        }
+        arrival.saturating_duration_since(now)
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Nodes that are fully disconnected from the network.
+    clogged_node: HashSet<NodeId>,
+    /// Node pairs that are disconnected from each other (order-independent).
+    clogged_link: HashSet<(NodeId, NodeId)>,
+    /// IP address of each registered node, used to translate a [`SocketAddr`]
+    /// back to the [`NodeId`] that owns it.
+    addr_to_node: HashMap<IpAddr, NodeId>,
+    /// Per-link quality overrides, keyed the same way as `clogged_link`.
+    links: HashMap<(NodeId, NodeId), LinkConfig>,
+    /// Applied to links with no explicit entry in `links`.
+    default_link: LinkConfig,
+    /// Next-free virtual time per link, for bandwidth serialization.
+    next_free: HashMap<(NodeId, NodeId), Instant>,
+    /// Bounded buffer of recent delays per link, used to realize
+    /// `LinkConfig::reorder_window`: a frame's delay is swapped with a
+    /// randomly-chosen still-buffered one once the buffer is full, so frames
+    /// in flight around the same time can be delivered out of send order.
+    reorder_buf: HashMap<(NodeId, NodeId), VecDeque<Duration>>,
+    /// Simulated DNS records, keyed by hostname.
+    hosts: HashMap<String, DnsRecord>,
+    /// Live connections, keyed by either endpoint's [`NodeId`], so that
+    /// resetting a node tears down every connection it's a party to.
+    connections: HashMap<NodeId, Vec<Weak<dyn ResetHandle>>>,
+}
+
+#[derive(Clone)]
+struct DnsRecord {
+    /// All addresses this name resolves to; round-robined across lookups.
+    addrs: Vec<SocketAddr>,
+    next: usize,
+    /// Simulated resolver latency.
+    latency: Duration,
+    /// Probability in `[0, 1]` that a lookup fails with a timeout
+    /// (`ErrorKind::TimedOut`) error.
+    failure_rate: f64,
+}
+
+fn link_key(a: NodeId, b: NodeId) -> (NodeId, NodeId) {
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+/// A simulator of the network.
+///
+/// It manages reachability between nodes (full or pairwise disconnects) and,
+/// on top of that, a per-link quality model (latency, bandwidth, loss and
+/// reordering) consulted whenever a frame is scheduled for delivery.
+#[derive(Default)]
+pub struct NetSim {
+    inner: Mutex<Inner>,
+}
+
+impl NetSim {
+    /// Registers the IP address owned by a node.
+    ///
+    /// Called by the runtime when a node is created; not normally called by tests.
+    pub fn register_node(&self, id: NodeId, ip: IpAddr) {
+        self.inner.lock().unwrap().addr_to_node.insert(ip, id);
+    }
+
+    pub(crate) fn node_of(&self, addr: SocketAddr) -> Option<NodeId> {
+        self.inner.lock().unwrap().addr_to_node.get(&addr.ip()).copied()
+    }
+
+    /// Disconnects the node from the network.
+    pub fn disconnect(&self, id: NodeId) {
+        self.inner.lock().unwrap().clogged_node.insert(id);
+    }
+
+    /// Reconnects the node to the network.
+    pub fn connect(&self, id: NodeId) {
+        self.inner.lock().unwrap().clogged_node.remove(&id);
+    }
+
+    /// Disconnects node `id1` from node `id2`.
+    pub fn disconnect2(&self, id1: NodeId, id2: NodeId) {
+        self.inner.lock().unwrap().clogged_link.insert(link_key(id1, id2));
+    }
+
+    /// Reconnects node `id1` to node `id2`.
+    pub fn connect2(&self, id1: NodeId, id2: NodeId) {
+        self.inner.lock().unwrap().clogged_link.remove(&link_key(id1, id2));
+    }
+
+    /// Resets a node: clears its clogged-link state as well as its full
+    /// disconnect, and tears down every live connection it's a party to
+    /// (e.g. any `TcpStream` it holds sees EOF, and any in-flight or future
+    /// `flush` on it reports the connection as reset).
+    pub fn reset_node(&self, id: NodeId) {
+        let handles = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.clogged_node.remove(&id);
+            inner.clogged_link.retain(|&(a, b)| a != id && b != id);
+            inner.connections.remove(&id).unwrap_or_default()
+        };
+        for handle in handles {
+            if let Some(handle) = handle.upgrade() {
+                handle.reset();
+            }
+        }
+    }
+
+    /// Registers a live connection so that resetting either `id1` or `id2`
+    /// tears it down. Only a weak reference is kept: once `handle` has no
+    /// more strong references the registration is skipped on reset, but it
+    /// stays in the node's list until that node is reset.
+    pub(crate) fn register_connection(&self, id1: NodeId, id2: NodeId, handle: Weak<dyn ResetHandle>) {
+        let mut inner = self.inner.lock().unwrap();
+        for id in [id1, id2] {
+            inner.connections.entry(id).or_default().push(handle.clone());
+        }
+    }
+
+    /// Returns whether `a` can currently reach `b`.
+    pub fn is_connected(&self, a: SocketAddr, b: SocketAddr) -> bool {
+        let (Some(ida), Some(idb)) = (self.node_of(a), self.node_of(b)) else {
+            // Addresses not owned by a known node (e.g. not yet registered):
+            // fail open, matching the previous binary-only behavior.
+            return true;
+        };
+        let inner = self.inner.lock().unwrap();
+        !inner.clogged_node.contains(&ida)
+            && !inner.clogged_node.contains(&idb)
+            && !inner.clogged_link.contains(&link_key(ida, idb))
+    }
+
+    /// Sets the link quality between two nodes.
+    pub fn set_link(&self, id1: NodeId, id2: NodeId, config: LinkConfig) {
+        self.inner.lock().unwrap().links.insert(link_key(id1, id2), config);
+    }
+
+    /// Sets the link quality applied to links with no explicit [`set_link`] override.
+    ///
+    /// [`set_link`]: NetSim::set_link
+    pub fn set_default_link(&self, config: LinkConfig) {
+        self.inner.lock().unwrap().default_link = config;
+    }
+
+    /// Rolls the link's drop probability once for a whole frame sent between
+    /// `a` and `b`. Callers that split a frame into multiple chunks (e.g. the
+    /// `Endpoint` priority scheduler) must call this once per frame and reuse
+    /// the result for every chunk, not re-roll per chunk.
+    pub(crate) fn should_drop(&self, a: SocketAddr, b: SocketAddr) -> bool {
+        let (Some(ida), Some(idb)) = (self.node_of(a), self.node_of(b)) else {
+            return false;
+        };
+        let key = link_key(ida, idb);
+        let inner = self.inner.lock().unwrap();
+        inner
+            .links
+            .get(&key)
+            .unwrap_or(&inner.default_link)
+            .should_drop()
+    }
+
+    /// Like [`schedule_delivery`](Self::schedule_delivery), but addressed by
+    /// [`SocketAddr`], applying the link's loss model. The `Endpoint` path
+    /// does not call this: it rolls loss once per frame with
+    /// [`should_drop`](Self::should_drop) and takes per-chunk timing from
+    /// [`delay_for_reliable`](Self::delay_for_reliable).
+    ///
+    /// Returns `Some(Duration::ZERO)` rather than failing when either address
+    /// isn't owned by a registered node, so unit tests that construct raw
+    /// endpoints without going through the runtime still see instant delivery.
+    pub(crate) fn delay_for(&self, a: SocketAddr, b: SocketAddr, len: usize) -> Option<Duration> {
+        match (self.node_of(a), self.node_of(b)) {
+            (Some(ida), Some(idb)) => self.schedule_delivery(ida, idb, len),
+            _ => Some(Duration::ZERO),
+        }
+    }
+
+    /// Like [`delay_for`](Self::delay_for), but never drops the frame: used
+    /// by the TCP stream write path, which (like a real `TcpStream`) must
+    /// never silently lose bytes it has accepted a write for, and by the
+    /// `Endpoint` scheduler for each chunk slot. Latency, jitter and
+    /// bandwidth serialization are still applied.
+    pub(crate) fn delay_for_reliable(&self, a: SocketAddr, b: SocketAddr, len: usize) -> Duration {
+        match (self.node_of(a), self.node_of(b)) {
+            (Some(ida), Some(idb)) => self.reliable_delay(ida, idb, len),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Computes the delivery delay for a frame of `len` bytes sent from
+    /// `id1` to `id2`, or `None` if the link's loss model drops it.
+    pub(crate) fn schedule_delivery(&self, id1: NodeId, id2: NodeId, len: usize) -> Option<Duration> {
+        let key = link_key(id1, id2);
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        let config = inner.links.get(&key).cloned().unwrap_or_else(|| inner.default_link.clone());
+        if config.should_drop() {
+            return None;
+        }
+        let next_free = inner.next_free.entry(key).or_insert(now);
+        let delay = config.delay(len, now, next_free);
+        Some(Self::reorder(&mut inner.reorder_buf, key, config.reorder_window, delay))
+    }
+
+    /// Like [`schedule_delivery`](Self::schedule_delivery), but never drops
+    /// the frame; used by the reliable TCP path.
+    fn reliable_delay(&self, id1: NodeId, id2: NodeId, len: usize) -> Duration {
+        let key = link_key(id1, id2);
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        let config = inner.links.get(&key).cloned().unwrap_or_else(|| inner.default_link.clone());
+        let next_free = inner.next_free.entry(key).or_insert(now);
+        let delay = config.delay(len, now, next_free);
+        Self::reorder(&mut inner.reorder_buf, key, config.reorder_window, delay)
+    }
+
+    /// Implements `LinkConfig::reorder_window`: once `window` delays are
+    /// buffered for this link, a new delay is swapped with a randomly-chosen
+    /// buffered one instead of being used directly, so frames sent close
+    /// together can be delivered out of order.
+    fn reorder(
+        buf: &mut HashMap<(NodeId, NodeId), VecDeque<Duration>>,
+        key: (NodeId, NodeId),
+        window: usize,
+        delay: Duration,
+    ) -> Duration {
+        if window == 0 {
+            return delay;
+        }
+        let queue = buf.entry(key).or_default();
+        if queue.len() >= window {
+            let idx = thread_rng().gen_range(0..queue.len());
+            let swapped = queue.remove(idx).unwrap();
+            queue.push_back(delay);
+            swapped
+        } else {
+            queue.push_back(delay);
+            delay
+        }
+    }
+
+    /// Registers a hostname that resolves to a single address.
+    pub fn register_host(&self, name: &str, addr: SocketAddr) {
+        self.register_host_multi(name, vec![addr]);
+    }
+
+    /// Registers a hostname that round-robins across multiple addresses.
+    pub fn register_host_multi(&self, name: &str, addrs: Vec<SocketAddr>) {
+        let mut inner = self.inner.lock().unwrap();
+        let record = inner.hosts.entry(name.to_owned()).or_insert(DnsRecord {
+            addrs: vec![],
+            next: 0,
+            latency: Duration::ZERO,
+            failure_rate: 0.0,
+        });
+        record.addrs = addrs;
+    }
+
+    /// Sets the simulated resolution latency for a registered hostname.
+    pub fn set_resolve_latency(&self, name: &str, latency: Duration) {
+        if let Some(record) = self.inner.lock().unwrap().hosts.get_mut(name) {
+            record.latency = latency;
+        }
+    }
+
+    /// Sets the probability that a lookup of `name` fails with a timeout
+    /// (`ErrorKind::TimedOut`). An injected failure is always a timeout; only
+    /// an unregistered name yields `ErrorKind::NotFound`.
+    pub fn set_resolve_failure_rate(&self, name: &str, rate: f64) {
+        if let Some(record) = self.inner.lock().unwrap().hosts.get_mut(name) {
+            record.failure_rate = rate;
+        }
+    }
+
+    /// Resolves `name` through the simulated DNS table.
+    ///
+    /// Applies the registered latency and failure rate (if any), and
+    /// round-robins across multi-record answers.
+    pub async fn resolve(&self, name: &str) -> io::Result<SocketAddr> {
+        let (latency, failure_rate) = {
+            let inner = self.inner.lock().unwrap();
+            let record = inner.hosts.get(name).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("unknown host: {name}"))
+            })?;
+            (record.latency, record.failure_rate)
+        };
+        if !latency.is_zero() {
+            crate::time::sleep(latency).await;
+        }
+        if failure_rate > 0.0 && thread_rng().gen_bool(failure_rate) {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("simulated resolution failure for {name}"),
+            ));
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let record = inner.hosts.get_mut(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("unknown host: {name}"))
+        })?;
+        if record.addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no records for {name}"),
+            ));
+        }
+        let addr = record.addrs[record.next % record.addrs.len()];
+        record.next = record.next.wrapping_add(1);
+        Ok(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{net::endpoint::Payload, plugin, runtime::Runtime, time::timeout};
+    use std::sync::Arc;
+    use tokio::sync::Barrier;
+
+    #[test]
+    fn link_latency_delays_delivery() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let id1 = node1.id();
+        let id2 = node2.id();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f1 = node1.spawn(async move {
+            plugin::simulator::<NetSim>().set_link(
+                id1,
+                id2,
+                LinkConfig {
+                    latency: Duration::from_millis(200),
+                    ..Default::default()
+                },
+            );
+            let ep = endpoint::Endpoint::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            ep.send_to_raw(addr2, 1, Box::new(42u32) as Payload)
+                .await
+                .unwrap();
+        });
+
+        let f2 = node2.spawn(async move {
+            let ep = endpoint::Endpoint::bind(addr2).await.unwrap();
+            barrier.wait().await;
+            let start = Instant::now();
+            let (msg, _) = ep.recv_from_raw(1).await.unwrap();
+            assert!(Instant::now().saturating_duration_since(start) >= Duration::from_millis(200));
+            assert_eq!(*msg.downcast::<u32>().unwrap(), 42);
+        });
+
+        runtime.block_on(f1).unwrap();
+        runtime.block_on(f2).unwrap();
+    }
+
+    #[test]
+    fn full_loss_drops_every_frame() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let id1 = node1.id();
+        let id2 = node2.id();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f1 = node1.spawn(async move {
+            plugin::simulator::<NetSim>().set_link(
+                id1,
+                id2,
+                LinkConfig {
+                    loss: 1.0,
+                    ..Default::default()
+                },
+            );
+            let ep = endpoint::Endpoint::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            ep.send_to_raw(addr2, 1, Box::new(()) as Payload)
+                .await
+                .unwrap();
+        });
+
+        let f2 = node2.spawn(async move {
+            let ep = endpoint::Endpoint::bind(addr2).await.unwrap();
+            barrier.wait().await;
+            timeout(Duration::from_secs(10), ep.recv_from_raw(1))
+                .await
+                .expect_err("a fully-lossy link must drop the frame, not merely delay it");
+        });
+
+        runtime.block_on(f1).unwrap();
+        runtime.block_on(f2).unwrap();
+    }
+
+    #[test]
+    fn bandwidth_serializes_transfers_on_the_same_link() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let id1 = node1.id();
+        let id2 = node2.id();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f1 = node1.spawn(async move {
+            // Slow enough that a single chunk-slot (CHUNK_SIZE bytes) takes
+            // 500ms to transmit; a 4-chunk frame should therefore occupy the
+            // link for ~2s before a message queued behind it can even start.
+            plugin::simulator::<NetSim>().set_link(
+                id1,
+                id2,
+                LinkConfig {
+                    bandwidth: Some(endpoint::CHUNK_SIZE as f64 * 2.0),
+                    ..Default::default()
+                },
+            );
+            let ep = endpoint::Endpoint::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            ep.send_to_raw_with_priority(
+                addr2,
+                1,
+                Box::new(()) as Payload,
+                Priority::NORMAL,
+                endpoint::CHUNK_SIZE * 4,
+            )
+            .await
+            .unwrap();
+            ep.send_to_raw_with_priority(addr2, 2, Box::new(()) as Payload, Priority::NORMAL, 0)
+                .await
+                .unwrap();
+        });
+
+        let f2 = node2.spawn(async move {
+            let ep = Arc::new(endpoint::Endpoint::bind(addr2).await.unwrap());
+            barrier.wait().await;
+            let start = Instant::now();
+            let ep_bulk = ep.clone();
+            let bulk = crate::task::spawn(async move {
+                ep_bulk.recv_from_raw(1).await.unwrap();
+                Instant::now()
+            });
+            let trailing = crate::task::spawn(async move {
+                ep.recv_from_raw(2).await.unwrap();
+                Instant::now()
+            });
+            let bulk_done = bulk.await.unwrap();
+            let trailing_done = trailing.await.unwrap();
+            assert!(
+                bulk_done.saturating_duration_since(start) >= Duration::from_millis(1_900),
+                "the 4-chunk bulk frame should take ~2s to transmit at this bandwidth"
+            );
+            assert!(
+                trailing_done.saturating_duration_since(bulk_done) >= Duration::from_millis(400),
+                "the trailing message must queue behind the bulk frame's full transmission time, \
+                 not be delivered independently of it"
+            );
+        });
+
+        runtime.block_on(f1).unwrap();
+        runtime.block_on(f2).unwrap();
+    }
+
+    #[test]
+    fn reorder_window_inverts_delivery_order() {
+        let runtime = Runtime::new();
+        let addr = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let node = runtime.create_node().ip(addr.ip()).build();
+        let key = link_key(node.id(), node.id());
+
+        let window = 2;
+        let mut buf = HashMap::new();
+
+        // A monotonically increasing sequence of per-frame delays, as a
+        // congested link's bandwidth serialization would assign to frames
+        // sent back-to-back.
+        let delays: Vec<Duration> = (1..=6u64).map(Duration::from_millis).collect();
+        let assigned: Vec<Duration> = delays
+            .iter()
+            .map(|&d| NetSim::reorder(&mut buf, key, window, d))
+            .collect();
+
+        // The first `window` frames only prime the buffer: there's nothing
+        // to swap with yet, so they pass through unchanged.
+        assert_eq!(&assigned[..window], &delays[..window]);
+
+        // From here on the buffer is full, so every call swaps in an
+        // already-buffered delay instead of the one just computed. Since the
+        // buffered delays are all strictly smaller (the input is monotonic
+        // increasing), each of these frames is assigned an earlier frame's
+        // delay -- i.e. it would be delivered out of its send order.
+        for i in window..delays.len() {
+            assert!(
+                assigned[i] < delays[i],
+                "frame {i} should have been assigned an earlier, smaller delay once the \
+                 reorder buffer filled, not its own {:?}",
+                delays[i]
+            );
+        }
+    }
+}
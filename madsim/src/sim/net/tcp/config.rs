@@ -0,0 +1,26 @@
+use std::time::Duration;
+
+/// Per-connection socket options for a simulated [`TcpStream`](super::TcpStream).
+///
+/// These mirror the subset of `std::net::TcpStream` / `socket2` options that real
+/// networking code commonly touches. All of them are "record and surface" only:
+/// they don't change the scheduling of packets in the simulator, but callers can
+/// still set and read them back so that code written against real sockets compiles
+/// and runs unmodified.
+#[derive(Debug, Clone)]
+pub(crate) struct SockConfig {
+    pub nodelay: bool,
+    pub ttl: u32,
+    pub linger: Option<Duration>,
+}
+
+impl Default for SockConfig {
+    fn default() -> Self {
+        SockConfig {
+            nodelay: false,
+            // matches the default TTL used by `std::net::TcpStream`
+            ttl: 64,
+            linger: None,
+        }
+    }
+}
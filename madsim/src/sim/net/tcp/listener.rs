@@ -0,0 +1,87 @@
+use super::stream::TcpStream;
+use crate::{net::NetSim, plugin};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::Mutex,
+};
+use tokio::sync::mpsc;
+
+/// Registry of listening sockets, keyed by their bound address.
+static LISTENERS: Lazy<Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<(TcpStream, SocketAddr)>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A simulated TCP socket server, listening for connections.
+pub struct TcpListener {
+    addr: SocketAddr,
+    rx: Mutex<mpsc::UnboundedReceiver<(TcpStream, SocketAddr)>>,
+}
+
+impl TcpListener {
+    /// Creates a new `TcpListener` bound to the given address, which may be a
+    /// [`SocketAddr`] or a hostname registered via
+    /// [`NetSim::register_host`](crate::net::NetSim::register_host).
+    pub async fn bind(addr: impl crate::net::ToSocketAddrs) -> io::Result<TcpListener> {
+        let addr = addr.to_socket_addr().await?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut listeners = LISTENERS.lock().unwrap();
+        if listeners.contains_key(&addr) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("address already in use: {addr}"),
+            ));
+        }
+        listeners.insert(addr, tx);
+        Ok(TcpListener {
+            addr,
+            rx: Mutex::new(rx),
+        })
+    }
+
+    /// Returns the local address that this listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    /// Accepts a new incoming connection.
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        self.rx
+            .lock()
+            .unwrap()
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "listener closed"))
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        LISTENERS.lock().unwrap().remove(&self.addr);
+    }
+}
+
+/// Connects to the given address, consulting [`NetSim`] for reachability
+/// before handing the caller a connected [`TcpStream`].
+pub(crate) async fn connect(addr: SocketAddr) -> io::Result<TcpStream> {
+    let net = plugin::simulator::<NetSim>();
+    let local_addr = crate::context::current_addr();
+    if !net.is_connected(local_addr, addr) {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("{local_addr} cannot reach {addr}"),
+        ));
+    }
+    let tx = {
+        let listeners = LISTENERS.lock().unwrap();
+        listeners
+            .get(&addr)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused"))?
+    };
+    let (ours, theirs) = TcpStream::new(local_addr, addr);
+    tx.send((theirs, local_addr))
+        .map_err(|_| io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused"))?;
+    Ok(ours)
+}
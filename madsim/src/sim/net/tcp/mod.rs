@@ -171,6 +171,50 @@ mod tests {
         runtime.block_on(f2).unwrap();
     }
 
+    #[test]
+    fn peek_then_read() {
+        let runtime = Runtime::new();
+        let addr1 = "10.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let addr2 = "10.0.0.2:1".parse::<SocketAddr>().unwrap();
+        let node1 = runtime.create_node().ip(addr1.ip()).build();
+        let node2 = runtime.create_node().ip(addr2.ip()).build();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_ = barrier.clone();
+
+        let f1 = node1.spawn(async move {
+            let listener = TcpListener::bind(addr1).await.unwrap();
+            barrier_.wait().await;
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // Give the peer a chance to start peeking before any data exists,
+            // so the peek has to park on the pipe's waker rather than
+            // observing data that was already there.
+            crate::time::sleep(Duration::from_millis(100)).await;
+            stream.write(b"hello").await.unwrap();
+            stream.flush().await.unwrap();
+        });
+
+        let f2 = node2.spawn(async move {
+            barrier.wait().await;
+            let mut stream = TcpStream::connect(addr1).await.unwrap();
+            let mut peek_buf = [0; 5];
+            // Peeking before any data has arrived must park this task
+            // (registering a waker), not return early or spin forever; it
+            // should resolve once the peer's write is delivered.
+            let n = timeout(Duration::from_secs(5), stream.peek(&mut peek_buf))
+                .await
+                .expect("peek should be woken once data arrives")
+                .unwrap();
+            assert_eq!(&peek_buf[..n], b"hello");
+            // The peeked bytes must still be there for a subsequent read.
+            let mut buf = [0; 5];
+            let len = stream.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], b"hello");
+        });
+
+        runtime.block_on(f1).unwrap();
+        runtime.block_on(f2).unwrap();
+    }
+
     #[test]
     fn reset() {
         let runtime = Runtime::new();
@@ -0,0 +1,442 @@
+use super::config::SockConfig;
+use crate::{
+    net::{NetSim, ResetHandle},
+    plugin,
+};
+use std::{
+    collections::VecDeque,
+    io,
+    net::{Shutdown, SocketAddr},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// One direction of a simulated byte pipe.
+#[derive(Default)]
+struct Pipe {
+    buf: VecDeque<u8>,
+    /// Set once this direction is shut down, by its writer
+    /// (`Shutdown::Write`) or by its reader (`Shutdown::Read`).
+    eof: bool,
+    /// The task currently parked waiting for more data (or EOF) on this pipe.
+    waker: Option<Waker>,
+}
+
+impl Pipe {
+    /// Wakes and clears the parked reader/peeker, if any.
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Shared state used to tear down both directions of a connection from
+/// outside, e.g. when [`NetSim::reset_node`] resets a node one of its
+/// endpoints lives on.
+///
+/// Kept separate from [`Pipe::eof`], which only ever reflects a *graceful*
+/// shutdown of one direction: a reset must be observable on both ends of
+/// the connection, whereas `eof` only affects the one direction it was set
+/// on.
+struct ConnReset {
+    flag: Arc<AtomicBool>,
+    a_to_b: Arc<Mutex<Pipe>>,
+    b_to_a: Arc<Mutex<Pipe>>,
+}
+
+impl ResetHandle for ConnReset {
+    fn reset(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+        self.a_to_b.lock().unwrap().wake();
+        self.b_to_a.lock().unwrap().wake();
+    }
+}
+
+struct ConnShared {
+    /// bytes written by us, read by the peer
+    outbound: Arc<Mutex<Pipe>>,
+    /// bytes written by the peer, read by us
+    inbound: Arc<Mutex<Pipe>>,
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr,
+    config: Mutex<SockConfig>,
+    /// Set by [`NetSim::reset_node`] (via [`ConnReset`]) on either side of the
+    /// connection. Unlike [`Pipe::eof`], this covers both directions, and
+    /// it fails `flush`.
+    reset: Arc<AtomicBool>,
+    /// Keeps the registered [`ConnReset`] handle alive for as long as either
+    /// end of the connection exists. `NetSim` only holds a `Weak` reference
+    /// to it (see [`TcpStream::new`]), so without a strong reference here the
+    /// handle would be dropped -- and the weak reference left dangling --
+    /// the moment `new()` returns, silently disabling reset propagation.
+    _reset_handle: Option<Arc<dyn ResetHandle>>,
+}
+
+/// A simulated TCP stream, mirroring [`tokio::net::TcpStream`].
+pub struct TcpStream {
+    shared: Arc<ConnShared>,
+}
+
+/// The readable half of a [`TcpStream`], created by [`TcpStream::into_split`].
+pub struct OwnedReadHalf {
+    shared: Arc<ConnShared>,
+}
+
+/// The writable half of a [`TcpStream`], created by [`TcpStream::into_split`].
+pub struct OwnedWriteHalf {
+    shared: Arc<ConnShared>,
+}
+
+/// The readable half of a [`TcpStream`], created by [`TcpStream::split`].
+pub struct ReadHalf<'a>(&'a TcpStream);
+/// The writable half of a [`TcpStream`], created by [`TcpStream::split`].
+pub struct WriteHalf<'a>(&'a TcpStream);
+
+impl TcpStream {
+    /// Connects to the given address, which may be a [`SocketAddr`] or a
+    /// hostname registered via [`NetSim::register_host`](crate::net::NetSim::register_host).
+    pub async fn connect(addr: impl crate::net::ToSocketAddrs) -> io::Result<TcpStream> {
+        let addr = addr.to_socket_addr().await?;
+        super::listener::connect(addr).await
+    }
+
+    pub(crate) fn new(local_addr: SocketAddr, peer_addr: SocketAddr) -> (TcpStream, TcpStream) {
+        let a_to_b = Arc::new(Mutex::new(Pipe::default()));
+        let b_to_a = Arc::new(Mutex::new(Pipe::default()));
+        let reset = Arc::new(AtomicBool::new(false));
+
+        // Register one reset handle for the whole connection, under both
+        // nodes: resetting either endpoint's node tears the connection down
+        // for both sides. Addresses not owned by a registered node (e.g. in
+        // unit tests that construct streams without a running node) simply
+        // aren't resettable.
+        //
+        // `NetSim` only ever holds a `Weak` reference to this handle, so a
+        // strong `Arc` to it is stashed in both `ConnShared`s below -- it
+        // must outlive this function, or the weak reference is dangling
+        // before `new()` even returns and resets silently never fire.
+        let net = plugin::simulator::<NetSim>();
+        let reset_handle: Option<Arc<dyn ResetHandle>> =
+            if let (Some(id1), Some(id2)) = (net.node_of(local_addr), net.node_of(peer_addr)) {
+                let handle: Arc<dyn ResetHandle> = Arc::new(ConnReset {
+                    flag: reset.clone(),
+                    a_to_b: a_to_b.clone(),
+                    b_to_a: b_to_a.clone(),
+                });
+                net.register_connection(id1, id2, Arc::downgrade(&handle));
+                Some(handle)
+            } else {
+                None
+            };
+
+        let a = TcpStream {
+            shared: Arc::new(ConnShared {
+                outbound: a_to_b.clone(),
+                inbound: b_to_a.clone(),
+                local_addr,
+                peer_addr,
+                config: Mutex::new(SockConfig::default()),
+                reset: reset.clone(),
+                _reset_handle: reset_handle.clone(),
+            }),
+        };
+        let b = TcpStream {
+            shared: Arc::new(ConnShared {
+                outbound: b_to_a,
+                inbound: a_to_b,
+                local_addr: peer_addr,
+                peer_addr: local_addr,
+                config: Mutex::new(SockConfig::default()),
+                reset,
+                _reset_handle: reset_handle,
+            }),
+        };
+
+        (a, b)
+    }
+
+    /// Returns the local address that this stream is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.shared.local_addr)
+    }
+
+    /// Returns the remote address that this stream is connected to.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.shared.peer_addr)
+    }
+
+    /// Sets the value of the `TCP_NODELAY` option on this socket.
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.shared.config.lock().unwrap().nodelay = nodelay;
+        Ok(())
+    }
+
+    /// Gets the value of the `TCP_NODELAY` option on this socket.
+    pub fn nodelay(&self) -> io::Result<bool> {
+        Ok(self.shared.config.lock().unwrap().nodelay)
+    }
+
+    /// Sets the value for the `IP_TTL` option on this socket.
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        self.shared.config.lock().unwrap().ttl = ttl;
+        Ok(())
+    }
+
+    /// Gets the value of the `IP_TTL` option for this socket.
+    pub fn ttl(&self) -> io::Result<u32> {
+        Ok(self.shared.config.lock().unwrap().ttl)
+    }
+
+    /// Sets the linger duration of this socket by setting the `SO_LINGER` option.
+    pub fn set_linger(&self, dur: Option<std::time::Duration>) -> io::Result<()> {
+        self.shared.config.lock().unwrap().linger = dur;
+        Ok(())
+    }
+
+    /// Reads the linger duration for this socket by getting the `SO_LINGER` option.
+    pub fn linger(&self) -> io::Result<Option<std::time::Duration>> {
+        Ok(self.shared.config.lock().unwrap().linger)
+    }
+
+    /// Receives data on the socket without removing it from the queue.
+    ///
+    /// Successive calls return the same data until `poll_read` consumes it.
+    pub async fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        std::future::poll_fn(|cx| self.poll_peek(cx, buf)).await
+    }
+
+    fn poll_peek(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut pipe = self.shared.inbound.lock().unwrap();
+        if !pipe.buf.is_empty() {
+            let n = buf.len().min(pipe.buf.len());
+            for (dst, src) in buf.iter_mut().zip(pipe.buf.iter()).take(n) {
+                *dst = *src;
+            }
+            return Poll::Ready(Ok(n));
+        }
+        if pipe.eof || self.shared.reset.load(Ordering::SeqCst) {
+            return Poll::Ready(Ok(0));
+        }
+        pipe.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Shuts down the read, write, or both halves of this connection.
+    ///
+    /// Shutting down the write half delivers EOF to the peer's next read;
+    /// shutting down the read half stops us from accepting more bytes, and
+    /// the peer's later writes fail with `BrokenPipe`.
+    pub async fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match how {
+            Shutdown::Write => {
+                let mut pipe = self.shared.outbound.lock().unwrap();
+                pipe.eof = true;
+                pipe.wake();
+            }
+            Shutdown::Read => {
+                let mut pipe = self.shared.inbound.lock().unwrap();
+                pipe.eof = true;
+                pipe.wake();
+            }
+            Shutdown::Both => {
+                let mut pipe = self.shared.outbound.lock().unwrap();
+                pipe.eof = true;
+                pipe.wake();
+                let mut pipe = self.shared.inbound.lock().unwrap();
+                pipe.eof = true;
+                pipe.wake();
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits the stream into owned read and write halves that may be moved
+    /// independently, e.g. to different tasks.
+    pub fn into_split(self) -> (OwnedReadHalf, OwnedWriteHalf) {
+        (
+            OwnedReadHalf {
+                shared: self.shared.clone(),
+            },
+            OwnedWriteHalf {
+                shared: self.shared,
+            },
+        )
+    }
+
+    /// Splits the stream into borrowed read and write halves.
+    pub fn split(&mut self) -> (ReadHalf<'_>, WriteHalf<'_>) {
+        let this: &TcpStream = self;
+        (ReadHalf(this), WriteHalf(this))
+    }
+}
+
+fn poll_read_pipe(
+    pipe: &Mutex<Pipe>,
+    reset: &AtomicBool,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+) -> Poll<io::Result<()>> {
+    let mut pipe = pipe.lock().unwrap();
+    if !pipe.buf.is_empty() {
+        let n = buf.remaining().min(pipe.buf.len());
+        for _ in 0..n {
+            buf.put_slice(&[pipe.buf.pop_front().unwrap()]);
+        }
+        return Poll::Ready(Ok(()));
+    }
+    if pipe.eof || reset.load(Ordering::SeqCst) {
+        return Poll::Ready(Ok(()));
+    }
+    pipe.waker = Some(cx.waker().clone());
+    Poll::Pending
+}
+
+/// Hands `buf` off to the network: consults [`NetSim`]'s per-link timing
+/// model for a delivery delay and, once that delay has elapsed, pushes the
+/// bytes into the peer-visible pipe.
+///
+/// Unlike the UDP-style `Endpoint` path, this never drops bytes: a `TcpStream`
+/// that has accepted a write must deliver it (or fail the connection
+/// entirely, surfaced through `poll_flush`/reads), matching real TCP.
+fn write_via_shared(shared: &Arc<ConnShared>, buf: &[u8]) -> io::Result<usize> {
+    {
+        let pipe = shared.outbound.lock().unwrap();
+        if pipe.eof {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "connection reset"));
+        }
+    }
+    let net = plugin::simulator::<NetSim>();
+    let delay = net.delay_for_reliable(shared.local_addr, shared.peer_addr, buf.len());
+    let pipe = shared.outbound.clone();
+    let reset = shared.reset.clone();
+    let data = buf.to_vec();
+    crate::task::spawn(async move {
+        if !delay.is_zero() {
+            crate::time::sleep(delay).await;
+        }
+        let mut pipe = pipe.lock().unwrap();
+        // A reset connection delivers nothing more: the peer sees EOF.
+        if !pipe.eof && !reset.load(Ordering::SeqCst) {
+            pipe.buf.extend(data);
+            pipe.wake();
+        }
+    })
+    .detach();
+    Ok(buf.len())
+}
+
+fn poll_flush_shared(shared: &ConnShared) -> Poll<io::Result<()>> {
+    if shared.reset.load(Ordering::SeqCst) {
+        return Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::ConnectionReset,
+            "connection reset by peer",
+        )));
+    }
+    Poll::Ready(Ok(()))
+}
+
+impl AsyncRead for TcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        poll_read_pipe(&self.shared.inbound, &self.shared.reset, cx, buf)
+    }
+}
+
+impl AsyncWrite for TcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(write_via_shared(&self.shared, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        poll_flush_shared(&self.shared)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut pipe = self.shared.outbound.lock().unwrap();
+        pipe.eof = true;
+        pipe.wake();
+        Poll::Ready(Ok(()))
+    }
+}
+
+macro_rules! impl_half {
+    ($ty:ident) => {
+        impl AsyncRead for $ty {
+            fn poll_read(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut ReadBuf<'_>,
+            ) -> Poll<io::Result<()>> {
+                poll_read_pipe(&self.shared.inbound, &self.shared.reset, cx, buf)
+            }
+        }
+
+        impl AsyncWrite for $ty {
+            fn poll_write(
+                self: Pin<&mut Self>,
+                _cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                Poll::Ready(write_via_shared(&self.shared, buf))
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                poll_flush_shared(&self.shared)
+            }
+
+            fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                let mut pipe = self.shared.outbound.lock().unwrap();
+                pipe.eof = true;
+                pipe.wake();
+                Poll::Ready(Ok(()))
+            }
+        }
+    };
+}
+
+impl_half!(OwnedReadHalf);
+impl_half!(OwnedWriteHalf);
+
+impl AsyncRead for ReadHalf<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        poll_read_pipe(&self.0.shared.inbound, &self.0.shared.reset, cx, buf)
+    }
+}
+
+impl AsyncWrite for WriteHalf<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(write_via_shared(&self.0.shared, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        poll_flush_shared(&self.0.shared)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut pipe = self.0.shared.outbound.lock().unwrap();
+        pipe.eof = true;
+        pipe.wake();
+        Poll::Ready(Ok(()))
+    }
+}